@@ -6,10 +6,75 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+/// The error type for every fallible frs operation, so callers embedding
+/// frs as a library can match on failure kinds instead of the process
+/// having already exited.
+#[derive(Debug)]
+pub enum FrsError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnknownBuilder {
+        name: String,
+        suggestion: Option<String>,
+    },
+    MissingContext {
+        namespace: String,
+        name: String,
+    },
+    AliasCycle(String),
+    Plugin(String),
+    NoSavedContexts,
+}
+
+impl Display for FrsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrsError::Io(err) => write!(f, "{}", err),
+            FrsError::Json(err) => write!(f, "{}", err),
+            FrsError::UnknownBuilder {
+                name,
+                suggestion: Some(suggestion),
+            } => write!(f, "unknown builder {}: did you mean '{}'?", name, suggestion),
+            FrsError::UnknownBuilder {
+                name,
+                suggestion: None,
+            } => write!(f, "unknown builder {}", name),
+            FrsError::MissingContext { namespace, name } => {
+                write!(f, "missing context {}::{}", namespace, name)
+            }
+            FrsError::AliasCycle(msg) => write!(f, "alias cycle detected: {}", msg),
+            FrsError::Plugin(msg) => write!(f, "{}", msg),
+            FrsError::NoSavedContexts => write!(f, "no saved contexts to choose from"),
+        }
+    }
+}
+
+impl std::error::Error for FrsError {}
+
+impl From<std::io::Error> for FrsError {
+    fn from(err: std::io::Error) -> Self {
+        FrsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for FrsError {
+    fn from(err: serde_json::Error) -> Self {
+        FrsError::Json(err)
+    }
+}
+
+/// Like `std::io::Result`, but for frs's own error type.
+pub type Result<T> = std::result::Result<T, FrsError>;
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct MetadataStepLog {
     pub description: String,
     pub prompt: Option<String>,
+    /// The exact text this step substituted for `TEMPLATE_PLACEHOLDER`, so
+    /// `rebuild_template` can replay the step without re-running its
+    /// builder.
+    #[serde(default)]
+    pub template_fragment: String,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -18,6 +83,10 @@ pub struct Metadata {
     pub name: String,
     pub is_dirty: bool,
     pub step_log: Vec<MetadataStepLog>,
+    /// Steps popped by `undo`, most recently undone last, so `redo` can
+    /// pop them back off in the right order.
+    #[serde(default)]
+    pub redo_stack: Vec<MetadataStepLog>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -59,6 +128,27 @@ impl Context {
     pub fn pretty_prompt(&self) -> PrettyPrompt {
         PrettyPrompt(self)
     }
+
+    /// Renders `self.env` as a `.env` file: one `KEY=value` line per entry,
+    /// quoting values that contain whitespace or quotes so the output can
+    /// be `source`d or fed to tools that expect a dotenv file.
+    pub fn to_dotenv(&self) -> String {
+        let mut dotenv = String::new();
+
+        for (key, value) in self.env.iter() {
+            dotenv.push_str(&format!("{}={}\n", key, dotenv_quote(value)));
+        }
+
+        dotenv
+    }
+}
+
+fn dotenv_quote(value: &str) -> String {
+    if value.chars().any(|c| c.is_whitespace() || c == '"' || c == '\'') {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_owned()
+    }
 }
 
 fn painted(f: &mut std::fmt::Formatter<'_>, c: ansi_term::Color, s: String) {
@@ -155,25 +245,134 @@ pub fn get_saved_context_path(namespace: &str, name: &str) -> String {
     )
 }
 
-pub fn load_context(namespace: &str, name: &str) -> Context {
+pub fn load_context(namespace: &str, name: &str) -> Result<Context> {
     let fi = get_saved_context_path(namespace, name);
 
-    let data = std::fs::read(fi)
-        .map_err(|err| {
-            eprintln!("load context failed {}::{}: {}", namespace, name, err);
-            std::process::exit(1);
-        })
-        .unwrap();
-    serde_json::from_slice(data.as_slice()).unwrap()
+    let data = std::fs::read(fi).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            FrsError::MissingContext {
+                namespace: namespace.to_owned(),
+                name: name.to_owned(),
+            }
+        } else {
+            FrsError::Io(err)
+        }
+    })?;
+    Ok(serde_json::from_slice(data.as_slice())?)
 }
 
-pub fn save_context(context: &Context) {
+pub fn save_context(context: &Context) -> Result<()> {
     let fi = get_saved_context_path(&context.meta.namespace, &context.meta.name);
     let dir = Path::new(&fi).parent().unwrap();
-    std::fs::create_dir_all(dir).unwrap();
+    std::fs::create_dir_all(dir)?;
 
-    let data = serde_json::to_vec(context).unwrap();
-    std::fs::write(fi, data).unwrap();
+    let data = serde_json::to_vec(context)?;
+    std::fs::write(fi, data)?;
+    Ok(())
+}
+
+/// Lists every saved context as `(namespace, name)`, by walking
+/// `~/.config/frs/context/<namespace>/<name>.json`.
+pub fn list_saved_contexts() -> Vec<(String, String)> {
+    let home = if cfg!(target_os = "windows") {
+        std::env::var("USERPROFILE").unwrap()
+    } else {
+        std::env::var("HOME").unwrap()
+    };
+    let base = Path::new(&home).join(".config/frs/context");
+
+    let mut contexts = Vec::new();
+    for namespace_entry in std::fs::read_dir(base).into_iter().flatten().flatten() {
+        if !namespace_entry.path().is_dir() {
+            continue;
+        }
+        let namespace = namespace_entry.file_name().to_string_lossy().into_owned();
+
+        for name_entry in std::fs::read_dir(namespace_entry.path())
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let path = name_entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            contexts.push((namespace.clone(), name));
+        }
+    }
+
+    contexts
+}
+
+/// Pipes every saved context as a `namespace::name` line to `$FRS_CHOOSER`
+/// (default `fzf`) and returns the one the user picked.
+pub fn choose_saved_context() -> Result<Option<(String, String)>> {
+    use std::io::Write;
+
+    let contexts = list_saved_contexts();
+    if contexts.is_empty() {
+        return Ok(None);
+    }
+
+    let chooser = std::env::var("FRS_CHOOSER").unwrap_or_else(|_| "fzf".to_owned());
+    let mut child = std::process::Command::new(&chooser)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for (namespace, name) in &contexts {
+            writeln!(stdin, "{}::{}", namespace, name)?;
+        }
+    }
+
+    let output = child.wait_with_output()?;
+    let chosen = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Ok(chosen
+        .split_once("::")
+        .map(|(namespace, name)| (namespace.to_owned(), name.to_owned())))
+}
+
+/// Replays `steps` from a fresh `TEMPLATE_PLACEHOLDER`, substituting each
+/// step's stored `template_fragment` in order. This is how `undo`/`redo`
+/// reconstruct `template` without re-invoking the builder that produced
+/// each step.
+fn rebuild_template(steps: &[MetadataStepLog]) -> String {
+    let mut template = TEMPLATE_PLACEHOLDER.to_owned();
+    for step in steps {
+        template = template.replace(TEMPLATE_PLACEHOLDER, &step.template_fragment);
+    }
+    template
+}
+
+/// Undoes the last `n` steps, moving them onto `redo_stack` and
+/// rebuilding `template` from what remains of `step_log`.
+pub fn undo(mut context: Context, n: usize) -> Context {
+    let n = n.min(context.meta.step_log.len());
+    for _ in 0..n {
+        if let Some(step) = context.meta.step_log.pop() {
+            context.meta.redo_stack.push(step);
+        }
+    }
+    context.template = rebuild_template(&context.meta.step_log);
+    context.meta.is_dirty = !context.meta.step_log.is_empty();
+    context
+}
+
+/// Redoes the last `n` undone steps, moving them back from `redo_stack`
+/// onto `step_log` and rebuilding `template`.
+pub fn redo(mut context: Context, n: usize) -> Context {
+    let n = n.min(context.meta.redo_stack.len());
+    for _ in 0..n {
+        if let Some(step) = context.meta.redo_stack.pop() {
+            context.meta.step_log.push(step);
+        }
+    }
+    context.template = rebuild_template(&context.meta.step_log);
+    context.meta.is_dirty = !context.meta.step_log.is_empty();
+    context
 }
 
 pub mod builtin {
@@ -186,20 +385,28 @@ pub mod builtin {
     }
 
     pub fn with_workdir(mut context: Context, workdir: String) -> Context {
+        let fragment = format!("(cd {};\n {})", workdir, crate::TEMPLATE_PLACEHOLDER);
         context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
         context.meta.step_log.push(MetadataStepLog {
             description: format!("core::with_workdir {:?}", workdir),
             prompt: Some(format!("wd(..{})", path_last(Path::new(&workdir)))),
+            template_fragment: fragment.clone(),
         });
-        context.template = context.template.replace(
-            crate::TEMPLATE_PLACEHOLDER,
-            &format!("(cd {};\n {})", workdir, crate::TEMPLATE_PLACEHOLDER),
-        );
+        context.template = context
+            .template
+            .replace(crate::TEMPLATE_PLACEHOLDER, &fragment);
         context
     }
 
     pub fn with_path(mut context: Context, path: String) -> Context {
+        let fragment = format!(
+            "(export PATH=${{PATH}}:{};\n {})",
+            path,
+            crate::TEMPLATE_PLACEHOLDER
+        );
         context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
         context.meta.step_log.push(MetadataStepLog {
             description: format!("core::with_path {:?}", path),
             prompt: Some({
@@ -211,72 +418,813 @@ pub mod builtin {
                         path_ref.parent().map(path_last).unwrap_or_else(|| "bin")
                     )
                 } else {
-                    format!("path({})", file_name.to_string())
+                    format!("path({})", file_name)
                 }
             }),
+            template_fragment: fragment.clone(),
         });
-        context.template = context.template.replace(
-            crate::TEMPLATE_PLACEHOLDER,
-            &format!(
-                "(export PATH=${{PATH}}:{};\n {})",
-                path,
-                crate::TEMPLATE_PLACEHOLDER
-            ),
-        );
+        context.template = context
+            .template
+            .replace(crate::TEMPLATE_PLACEHOLDER, &fragment);
         context
     }
 
     pub fn with_env(mut context: Context, key: String, value: String) -> Context {
+        let fragment = format!(
+            "(export {}={};\n {})",
+            key,
+            value,
+            crate::TEMPLATE_PLACEHOLDER
+        );
         context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
         context.meta.step_log.push(MetadataStepLog {
             description: format!("core::with_env {:?}={:?}", key, value),
             prompt: Some(format!("env({})", key)),
+            template_fragment: fragment.clone(),
         });
-        context.template = context.template.replace(
-            crate::TEMPLATE_PLACEHOLDER,
-            &format!(
-                "(export {}={};\n {})",
-                key,
-                value,
-                crate::TEMPLATE_PLACEHOLDER
-            ),
-        );
+        context.template = context
+            .template
+            .replace(crate::TEMPLATE_PLACEHOLDER, &fragment);
 
         context
     }
 
     pub fn with_command(mut context: Context, cmd: String) -> Context {
         let cmd_first = cmd.split_whitespace().next().unwrap_or("");
+        let fragment = format!("({};\n {})", cmd, crate::TEMPLATE_PLACEHOLDER);
         context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
         context.meta.step_log.push(MetadataStepLog {
             description: format!("core::with_command {:?}", cmd),
             prompt: Some(format!("exec({})", cmd_first)),
+            template_fragment: fragment.clone(),
         });
-        context.template = context.template.replace(
-            crate::TEMPLATE_PLACEHOLDER,
-            &format!("({};\n {})", cmd, crate::TEMPLATE_PLACEHOLDER),
-        );
+        context.template = context
+            .template
+            .replace(crate::TEMPLATE_PLACEHOLDER, &fragment);
         context
     }
 
     pub fn with_docker(mut context: Context, container: String) -> Context {
+        let fragment = format!("(docker run {} {})", container, crate::TEMPLATE_PLACEHOLDER);
         context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
         context.meta.step_log.push(MetadataStepLog {
             description: format!("core::with_docker {:?}", container),
             prompt: Some(format!("ctr({:?})", container)),
+            template_fragment: fragment.clone(),
         });
-        context.template = context.template.replace(
-            crate::TEMPLATE_PLACEHOLDER,
-            &format!("(docker run {} {})", container, crate::TEMPLATE_PLACEHOLDER),
-        );
+        context.template = context
+            .template
+            .replace(crate::TEMPLATE_PLACEHOLDER, &fragment);
         context
     }
 
-    pub fn activate_context(_context: Context, namespace: &str, name: &str) -> Context {
+    pub fn with_dotenv(mut context: Context, path: String) -> crate::Result<Context> {
+        let data = std::fs::read_to_string(&path)?;
+
+        let mut count = 0usize;
+        let mut fragment = crate::TEMPLATE_PLACEHOLDER.to_owned();
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let key = key.trim();
+            let value = strip_dotenv_quotes(value.trim());
+
+            let substitution = format!(
+                "(export {}={};\n {})",
+                key,
+                value,
+                crate::TEMPLATE_PLACEHOLDER
+            );
+            context.template = context
+                .template
+                .replace(crate::TEMPLATE_PLACEHOLDER, &substitution);
+            fragment = fragment.replace(crate::TEMPLATE_PLACEHOLDER, &substitution);
+            count += 1;
+        }
+
+        context.meta.is_dirty = true;
+        context.meta.redo_stack.clear();
+        context.meta.step_log.push(MetadataStepLog {
+            description: format!("core::with_dotenv {:?}", path),
+            prompt: Some(format!("dotenv({}:{})", path_last(Path::new(&path)), count)),
+            template_fragment: fragment,
+        });
+
+        Ok(context)
+    }
+
+    fn strip_dotenv_quotes(value: &str) -> &str {
+        let bytes = value.as_bytes();
+        if bytes.len() >= 2 {
+            let first = bytes[0];
+            let last = bytes[bytes.len() - 1];
+            if (first == b'"' && last == b'"') || (first == b'\'' && last == b'\'') {
+                return &value[1..value.len() - 1];
+            }
+        }
+        value
+    }
+
+    pub fn activate_context(_context: Context, namespace: &str, name: &str) -> crate::Result<Context> {
         load_context(namespace, name)
     }
 }
 
+/// "Did you mean?" suggestions for mistyped builder names, using plain
+/// Levenshtein edit distance over the candidate strings.
+pub mod suggest {
+    /// `d[i][j]` is the edit distance between `a[..i]` and `b[..j]`.
+    pub fn levenshtein(a: &str, b: &str) -> usize {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+
+        let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for (i, row) in d.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, cell) in d[0].iter_mut().enumerate() {
+            *cell = j;
+        }
+
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                d[i][j] = (d[i - 1][j] + 1)
+                    .min(d[i][j - 1] + 1)
+                    .min(d[i - 1][j - 1] + cost);
+            }
+        }
+
+        d[a.len()][b.len()]
+    }
+
+    /// Returns the closest candidate to `name` if it's within
+    /// `max(2, len(name) / 3)` edits, `None` otherwise.
+    pub fn suggest<S: AsRef<str>>(name: &str, candidates: impl IntoIterator<Item = S>) -> Option<String> {
+        let threshold = (name.chars().count() / 3).max(2);
+        candidates
+            .into_iter()
+            .map(|c| (c.as_ref().to_owned(), levenshtein(name, c.as_ref())))
+            .filter(|(_, dist)| *dist <= threshold)
+            .min_by_key(|(_, dist)| *dist)
+            .map(|(candidate, _)| candidate)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_levenshtein() {
+            assert_eq!(levenshtein("docker", "docker"), 0);
+            assert_eq!(levenshtein("dokcer", "docker"), 2);
+            assert_eq!(levenshtein("", "abc"), 3);
+        }
+
+        #[test]
+        fn test_suggest() {
+            let builders = ["workdir", "path", "env", "command", "docker", "empty"];
+            assert_eq!(suggest("dokcer", builders), Some("docker".to_owned()));
+            assert_eq!(suggest("zzzzzzzzzz", builders), None);
+        }
+    }
+}
+
+/// User config, currently just named aliases that expand to a sequence of
+/// existing builder invocations (`~/.config/frs/config.json`).
+pub mod config {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct Config {
+        #[serde(default)]
+        pub alias: HashMap<String, Vec<String>>,
+    }
+
+    pub fn config_path() -> PathBuf {
+        let home = if cfg!(target_os = "windows") {
+            std::env::var("USERPROFILE").unwrap()
+        } else {
+            std::env::var("HOME").unwrap()
+        };
+        Path::new(&home).join(".config/frs/config.json")
+    }
+
+    pub fn load_config() -> crate::Result<Config> {
+        match std::fs::read(config_path()) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Expands `name` into its flat list of builder invocations, following
+    /// at most one level of alias-to-alias reference. Returns `None` if
+    /// `name` isn't a known alias. Errors on a self-reference and on any
+    /// chain deeper than one level, cyclic or not.
+    pub fn resolve_alias(config: &Config, name: &str) -> crate::Result<Option<Vec<String>>> {
+        let steps = match config.alias.get(name) {
+            Some(steps) => steps,
+            None => return Ok(None),
+        };
+        let mut expanded = Vec::with_capacity(steps.len());
+
+        for step in steps {
+            let builder = step.split_whitespace().next().unwrap_or("");
+            if builder == name {
+                return Err(crate::FrsError::AliasCycle(format!(
+                    "{0} references {0}",
+                    name
+                )));
+            }
+
+            match config.alias.get(builder) {
+                Some(inner_steps) => {
+                    for inner_step in inner_steps {
+                        let inner_builder = inner_step.split_whitespace().next().unwrap_or("");
+                        if config.alias.contains_key(inner_builder) {
+                            return Err(crate::FrsError::AliasCycle(format!(
+                                "{} -> {} -> {} (only one level of alias-to-alias is supported)",
+                                name, builder, inner_builder
+                            )));
+                        }
+                        expanded.push(inner_step.clone());
+                    }
+                }
+                None => expanded.push(step.clone()),
+            }
+        }
+
+        Ok(Some(expanded))
+    }
+}
+
+/// External builders implemented as plugin executables, spoken to over a
+/// line-delimited JSON-RPC protocol on stdin/stdout.
+pub mod plugin {
+    use std::io::{BufRead, BufReader, Write};
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::Context;
+
+    #[derive(Debug, Serialize)]
+    struct TransformParams {
+        context: Context,
+        args: Vec<String>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct RpcRequest<P> {
+        method: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        params: Option<P>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct RpcResponse<R> {
+        result: R,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TransformResult {
+        context: Context,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Describe {
+        pub name: String,
+        pub help: String,
+        pub prompt_segment: String,
+    }
+
+    pub fn plugin_dir() -> PathBuf {
+        let home = if cfg!(target_os = "windows") {
+            std::env::var("USERPROFILE").unwrap()
+        } else {
+            std::env::var("HOME").unwrap()
+        };
+        Path::new(&home).join(".config/frs/plugins")
+    }
+
+    pub fn plugin_path(name: &str) -> PathBuf {
+        plugin_dir().join(name)
+    }
+
+    pub fn has_plugin(name: &str) -> bool {
+        plugin_path(name).is_file()
+    }
+
+    /// Names of the plugins currently installed under the plugin directory,
+    /// used to widen the "did you mean?" candidate set.
+    pub fn list_plugins() -> Vec<String> {
+        std::fs::read_dir(plugin_dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect()
+    }
+
+    fn call<P: Serialize, R: for<'de> Deserialize<'de>>(
+        name: &str,
+        method: &'static str,
+        params: Option<P>,
+    ) -> crate::Result<R> {
+        let mut child = Command::new(plugin_path(name))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let request = RpcRequest { method, params };
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        child.stdin.take().unwrap().write_all(line.as_bytes())?;
+
+        let mut reply = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut reply)?;
+        child.wait()?;
+
+        let response: RpcResponse<R> = serde_json::from_str(reply.trim_end()).map_err(|err| {
+            crate::FrsError::Plugin(format!(
+                "plugin {} returned malformed response: {}",
+                name, err
+            ))
+        })?;
+        Ok(response.result)
+    }
+
+    pub fn describe(name: &str) -> crate::Result<Describe> {
+        call::<(), Describe>(name, "describe", None)
+    }
+
+    /// Spawns `name` from the plugin directory and hands it the context to
+    /// transform. The plugin is responsible for appending its own
+    /// `MetadataStepLog`, exactly like a `builtin::with_*` function would.
+    /// If the plugin left `prompt` unset on the step it pushed, we fall
+    /// back to the `prompt_segment` it advertises via `describe`, so every
+    /// plugin shows up in `pretty_prompt` even if it doesn't bother.
+    pub fn with_plugin(context: Context, name: &str, args: Vec<String>) -> crate::Result<Context> {
+        let mut result: TransformResult =
+            call(name, "transform", Some(TransformParams { context, args }))?;
+
+        if let Some(step) = result.context.meta.step_log.last_mut() {
+            if step.prompt.is_none() {
+                if let Ok(info) = describe(name) {
+                    step.prompt = Some(info.prompt_segment);
+                }
+            }
+        }
+
+        Ok(result.context)
+    }
+}
+
+/// The embeddable frs CLI: argument types plus a fallible `run` entry
+/// point, so both the `frs` binary and other programs (or integration
+/// tests) can drive the whole pipeline without the process exiting out
+/// from under them. `main` is kept to the thin wrapper that parses argv,
+/// calls `run`, and translates the result into exit codes and stderr.
+pub mod cli {
+    use std::path::Path;
+
+    use clap::{Parser, Subcommand};
+    use once_cell::sync::Lazy;
+
+    use crate::{builtin, config, plugin, suggest, Context, FrsError, Result, TEMPLATE_PLACEHOLDER};
+
+    pub mod build_info {
+        /// The version of the frs crate.
+        pub static VERSION: &str = env!("CARGO_PKG_VERSION");
+    }
+
+    #[derive(Debug, Parser)]
+    #[clap(name = "frs", version = build_info::VERSION)]
+    pub struct Opts {
+        #[clap(subcommand)]
+        pub sub: Option<Subcommands>,
+    }
+
+    #[derive(Debug, Subcommand)]
+    #[clap(
+        about = "The cli for frs.",
+        after_help = "",
+        next_display_order = None
+    )]
+    #[allow(clippy::large_enum_variant)]
+    pub enum Subcommands {
+        /// Manipulate context.
+        With(WithArgs),
+
+        /// Run with context.
+        Run(RunArgs),
+
+        /// Save context.
+        Save(SaveArgs),
+
+        /// Inspect context.
+        Inspect(InspectArgs),
+
+        /// Export context in another format.
+        Export(InspectArgs),
+
+        /// Get Propmt of context.
+        Prompt,
+
+        /// Undo the last step(s) in context.
+        Undo(UndoArgs),
+
+        /// Redo the last undone step(s) in context.
+        Redo(RedoArgs),
+    }
+
+    #[derive(Debug, Clone, Copy, clap::ValueEnum)]
+    pub enum ExportFormat {
+        /// The colored, human-oriented rendering used by `inspect`.
+        Pretty,
+        /// The `Context`'s own serde representation.
+        Json,
+        /// The `to_shell` rendering: the template with its placeholder left
+        /// in, followed by the step log and metadata as comments.
+        Shell,
+        /// `KEY=value` lines derived from `self.env`, suitable for `source`.
+        Dotenv,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct WithArgs {
+        #[clap(index = 1, help = "The context builder.")]
+        pub builder: String,
+
+        #[clap(index = 2, trailing_var_arg = true, help = "The rest arguments.")]
+        pub rest: Vec<String>,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct RunArgs {
+        #[clap(long, help = "Using context", default_value = "default")]
+        pub context: String,
+
+        #[clap(long, help = "Show executing command", default_value_t = false)]
+        pub show: bool,
+
+        #[clap(index = 1, trailing_var_arg = true, help = "The rest arguments.")]
+        pub rest: Vec<String>,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct SaveArgs {
+        #[clap(long, help = "Save into namespace.", default_value = "default")]
+        pub namespace: String,
+        #[clap(index = 1, help = "Save as name.")]
+        pub name: String,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct InspectArgs {
+        #[clap(
+            long,
+            help = "Inspect context within namespace.",
+            default_value = "default"
+        )]
+        pub namespace: String,
+        #[clap(
+            index = 1,
+            help = "Inspect by context name.",
+            default_value = "default"
+        )]
+        pub name: String,
+        #[clap(long, value_enum, help = "Output format.", default_value = "pretty")]
+        pub format: ExportFormat,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct UndoArgs {
+        #[clap(index = 1, help = "Number of steps to undo.", default_value_t = 1)]
+        pub n: usize,
+    }
+
+    #[derive(Debug, Parser)]
+    pub struct RedoArgs {
+        #[clap(index = 1, help = "Number of steps to redo.", default_value_t = 1)]
+        pub n: usize,
+    }
+
+    fn get_context_from_file(fi: &Path) -> std::io::Result<Context> {
+        let data = std::fs::read(fi)?;
+        serde_json::from_slice(data.as_slice())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    fn get_base_context() -> Context {
+        let mut base_state = Context::default();
+        base_state.meta.name = "default".to_owned();
+        base_state.meta.namespace = "default".to_owned();
+        base_state
+            .env
+            .insert("FRS_VERSION".to_string(), build_info::VERSION.to_owned());
+        base_state.template = TEMPLATE_PLACEHOLDER.to_owned();
+        base_state
+    }
+
+    static CURRENT_STAT_PATH: Lazy<String> = Lazy::new(|| {
+        // get if frs term_pid env is set
+        let parent_pid: u32 = if let Ok(pid) = std::env::var("FRS_TERM_PID") {
+            pid.parse().unwrap()
+        } else if cfg!(all(unix)) {
+            std::os::unix::process::parent_id()
+        } else {
+            unimplemented!()
+        };
+        let start_time: u64 = if cfg!(all(unix)) {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", parent_pid)).unwrap();
+            stat.split(' ').nth(21).unwrap().parse().unwrap()
+        } else {
+            unimplemented!()
+        };
+
+        format!("/tmp/{}.{}.json", parent_pid, start_time)
+    });
+
+    /// Get current shell state
+    fn get_current_shell_context() -> Context {
+        let state_file = &*CURRENT_STAT_PATH;
+        if let Ok(context) = get_context_from_file(std::path::Path::new(state_file)) {
+            return context;
+        }
+
+        get_base_context()
+    }
+
+    fn persist_current_shell_context(context: &Context) -> Result<()> {
+        let stat_file = &*CURRENT_STAT_PATH;
+        let data = serde_json::to_vec(context)?;
+        std::fs::write(stat_file, data)?;
+        Ok(())
+    }
+
+    const KNOWN_BUILDERS: &[&str] = &[
+        "workdir", "path", "env", "command", "docker", "dotenv", "context", "empty",
+    ];
+
+    /// Applies a single builder invocation (as `frs with <builder>
+    /// <rest...>` would) to `context`. Shared between the top-level
+    /// dispatch and alias expansion so an alias step is handled identically
+    /// to typing it by hand.
+    fn apply_builder(context: Context, builder: &str, rest: Vec<String>) -> Result<Context> {
+        let rest_as_args = || {
+            std::iter::once(std::env::args().next().unwrap())
+                .chain(std::iter::once(builder.to_owned()))
+                .chain(rest.iter().cloned())
+                .collect::<Vec<_>>()
+        };
+
+        let context = match builder {
+            "workdir" => {
+                #[derive(Debug, Parser)]
+                pub struct WithWorkdirArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(index = 2, help = "new workdir.")]
+                    pub workdir: String,
+                }
+
+                let opts = WithWorkdirArgs::parse_from(rest_as_args());
+                builtin::with_workdir(context, opts.workdir)
+            }
+            "path" => {
+                #[derive(Debug, Parser)]
+                pub struct WithPathArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(index = 2, help = "new path.")]
+                    pub path: String,
+                }
+
+                let opts = WithPathArgs::parse_from(rest_as_args());
+                builtin::with_path(context, opts.path)
+            }
+            "env" => {
+                #[derive(Debug, Parser)]
+                pub struct WithEnvArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(index = 2, help = "new env key.")]
+                    pub key: String,
+                    #[clap(index = 3, help = "new env value.")]
+                    pub value: String,
+                }
+
+                let opts = WithEnvArgs::parse_from(rest_as_args());
+                builtin::with_env(context, opts.key, opts.value)
+            }
+            "command" => builtin::with_command(context, rest.join(" ")),
+            "docker" => {
+                #[derive(Debug, Parser)]
+                pub struct WithDockerArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(index = 2, help = "new docker container.")]
+                    pub container: String,
+                }
+
+                let opts = WithDockerArgs::parse_from(rest_as_args());
+                builtin::with_docker(context, opts.container)
+            }
+            "dotenv" => {
+                #[derive(Debug, Parser)]
+                pub struct WithDotenvArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(index = 2, help = "dotenv file to load.")]
+                    pub path: String,
+                }
+
+                let opts = WithDotenvArgs::parse_from(rest_as_args());
+                builtin::with_dotenv(context, opts.path)?
+            }
+            "context" if rest.is_empty() => match crate::choose_saved_context()? {
+                Some((namespace, name)) => builtin::activate_context(context, &namespace, &name)?,
+                None => return Err(FrsError::NoSavedContexts),
+            },
+            "context" => {
+                #[derive(Debug, Parser)]
+                pub struct WithContextArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                    #[clap(long, help = "new context namespace.", default_value = "default")]
+                    pub namespace: String,
+                    #[clap(index = 2, help = "new context name.")]
+                    pub name: String,
+                }
+
+                let opts = WithContextArgs::parse_from(rest_as_args());
+                builtin::activate_context(context, &opts.namespace, &opts.name)?
+            }
+            "empty" => {
+                #[derive(Debug, Parser)]
+                pub struct WithContextArgs {
+                    #[clap(index = 1, help = "_")]
+                    pub self_arg: String,
+                }
+
+                let _opts = WithContextArgs::parse_from(rest_as_args());
+                get_base_context()
+            }
+            _ if plugin::has_plugin(builder) => plugin::with_plugin(context, builder, rest)?,
+            _ => {
+                let mut known: Vec<String> = KNOWN_BUILDERS.iter().map(|b| b.to_string()).collect();
+                known.extend(plugin::list_plugins());
+
+                return Err(FrsError::UnknownBuilder {
+                    name: builder.to_owned(),
+                    suggestion: suggest::suggest(builder, known),
+                });
+            }
+        };
+
+        Ok(context)
+    }
+
+    /// Builds the context `args` describes and persists it as the current
+    /// shell state. Returns the resulting context for callers (e.g. tests)
+    /// that want to assert on it; the CLI itself has nothing to print here.
+    pub fn with_context(args: WithArgs) -> Result<Context> {
+        let context = get_current_shell_context();
+
+        // Aliases only ever apply to names that aren't already a builtin or
+        // a plugin, so a malformed `config.json` can't take down unrelated
+        // builders like `frs with workdir /tmp` — we simply never read it.
+        let is_builtin_or_plugin =
+            KNOWN_BUILDERS.contains(&args.builder.as_str()) || plugin::has_plugin(&args.builder);
+
+        let context = if is_builtin_or_plugin {
+            apply_builder(context, &args.builder, args.rest.clone())?
+        } else {
+            let config = config::load_config()?;
+            match config::resolve_alias(&config, &args.builder)? {
+                Some(steps) => {
+                    let mut context = context;
+                    for step in steps {
+                        let mut parts = step.split_whitespace();
+                        let builder = parts.next().unwrap_or("").to_owned();
+                        let rest = parts.map(String::from).collect();
+                        context = apply_builder(context, &builder, rest)?;
+                    }
+                    context
+                }
+                None => apply_builder(context, &args.builder, args.rest.clone())?,
+            }
+        };
+
+        persist_current_shell_context(&context)?;
+        Ok(context)
+    }
+
+    fn run_context(args: RunArgs) -> Result<String> {
+        let context = get_current_shell_context();
+        let command_str = args.rest.join(" ");
+        let _ = args.show;
+        Ok(context.template.replace(TEMPLATE_PLACEHOLDER, &command_str))
+    }
+
+    /// Saves the current shell state as `namespace::name`. Returns the
+    /// saved context for the same reason as `with_context`.
+    pub fn save_context(args: SaveArgs) -> Result<Context> {
+        let mut context = get_current_shell_context();
+        context.meta.namespace = args.namespace.clone();
+        context.meta.name = args.name.clone();
+        context.meta.is_dirty = false;
+
+        crate::save_context(&context)?;
+        persist_current_shell_context(&context)?;
+        Ok(context)
+    }
+
+    /// Undoes the last `args.n` steps of the current shell context and
+    /// persists the result.
+    fn undo_context(args: UndoArgs) -> Result<Context> {
+        let context = crate::undo(get_current_shell_context(), args.n);
+        persist_current_shell_context(&context)?;
+        Ok(context)
+    }
+
+    /// Redoes the last `args.n` undone steps of the current shell context
+    /// and persists the result.
+    fn redo_context(args: RedoArgs) -> Result<Context> {
+        let context = crate::redo(get_current_shell_context(), args.n);
+        persist_current_shell_context(&context)?;
+        Ok(context)
+    }
+
+    fn inspect_context(args: InspectArgs) -> Result<String> {
+        let context = match args.name.as_str() {
+            "default" if args.namespace == "default" => get_current_shell_context(),
+            "default" => {
+                return Err(FrsError::MissingContext {
+                    namespace: args.namespace,
+                    name: args.name,
+                })
+            }
+            _ => crate::load_context(&args.namespace, &args.name)?,
+        };
+
+        Ok(match args.format {
+            ExportFormat::Pretty => context.pretty_print().to_string(),
+            ExportFormat::Json => serde_json::to_string_pretty(&context)?,
+            ExportFormat::Shell => context.to_shell(),
+            ExportFormat::Dotenv => context.to_dotenv(),
+        })
+    }
+
+    fn prompt_context() -> Result<String> {
+        Ok(get_current_shell_context().pretty_prompt().to_string())
+    }
+
+    /// Drives one subcommand end to end and returns what the CLI should
+    /// print on stdout (empty for the side-effecting `with`/`save`/`undo`/
+    /// `redo` commands). `None` subcommand (bare `frs`) is handled by the
+    /// caller, which has clap report the missing subcommand and exit.
+    pub fn run(sub: Subcommands) -> Result<String> {
+        Ok(match sub {
+            Subcommands::With(args) => {
+                with_context(args)?;
+                String::new()
+            }
+            Subcommands::Run(args) => run_context(args)?,
+            Subcommands::Save(args) => {
+                save_context(args)?;
+                String::new()
+            }
+            Subcommands::Inspect(args) => inspect_context(args)?,
+            Subcommands::Export(args) => inspect_context(args)?,
+            Subcommands::Prompt => prompt_context()?,
+            Subcommands::Undo(args) => {
+                undo_context(args)?;
+                String::new()
+            }
+            Subcommands::Redo(args) => {
+                redo_context(args)?;
+                String::new()
+            }
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -289,6 +1237,7 @@ mod tests {
                 name: "".to_owned(),
                 is_dirty: false,
                 step_log: vec![],
+                redo_stack: vec![],
             },
             env: HashMap::new(),
             template: String::from("echo hello"),